@@ -1,12 +1,278 @@
 use crate::device::RawDevice;
 use ash::{prelude::VkResult, version::DeviceV1_0, vk};
-use std::{ffi::CString, sync::Arc};
+use std::{
+    ffi::CString,
+    fs,
+    io::Write,
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// Magic tag written at the start of every on-disk cache blob so a file that
+/// is not ours (or is truncated) is rejected before we hand it to the driver.
+const PIPELINE_CACHE_MAGIC: u32 = 0x_504c_4b41; // "PLKA"
+
+/// Header prepended to the raw `vk::PipelineCache` data on disk. The driver is
+/// free to reject a blob built on a different GPU or driver, but doing the
+/// check ourselves avoids even handing it mismatched bytes.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PipelineCacheHeader {
+    magic: u32,
+    vendor_id: u32,
+    device_id: u32,
+    cache_uuid: [u8; vk::UUID_SIZE],
+}
+
+impl PipelineCacheHeader {
+    fn new(properties: &vk::PhysicalDeviceProperties) -> Self {
+        Self {
+            magic: PIPELINE_CACHE_MAGIC,
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            cache_uuid: properties.pipeline_cache_uuid,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        // Safety: `Self` is `repr(C)` and plain-old-data.
+        unsafe {
+            std::slice::from_raw_parts(
+                (self as *const Self) as *const u8,
+                std::mem::size_of::<Self>(),
+            )
+        }
+    }
+
+    fn read(bytes: &[u8]) -> Option<(Self, &[u8])> {
+        let size = std::mem::size_of::<Self>();
+        if bytes.len() < size {
+            return None;
+        }
+        let (head, rest) = bytes.split_at(size);
+        // Safety: we just checked `head` is at least `size_of::<Self>()` bytes
+        // and `Self` is `repr(C)` plain-old-data.
+        let header = unsafe { std::ptr::read_unaligned(head.as_ptr() as *const Self) };
+        Some((header, rest))
+    }
+
+    fn matches(&self, properties: &vk::PhysicalDeviceProperties) -> bool {
+        self.magic == PIPELINE_CACHE_MAGIC
+            && self.vendor_id == properties.vendor_id
+            && self.device_id == properties.device_id
+            && self.cache_uuid == properties.pipeline_cache_uuid
+    }
+}
+
+/// A persistent `vk::PipelineCache`. On creation it seeds the driver cache with
+/// a previously saved blob (if one exists and matches this GPU/driver), and on
+/// drop it writes the accumulated cache back to disk so the next launch and
+/// every shader reload skip most of the driver's pipeline-build cost.
+pub struct PipelineCache {
+    pub cache: vk::PipelineCache,
+    path: PathBuf,
+    header: PipelineCacheHeader,
+    device: Arc<RawDevice>,
+}
+
+impl PipelineCache {
+    /// Load the cache for this physical device from `path`, falling back to an
+    /// empty cache when the file is absent or was built on another GPU.
+    pub fn new(
+        path: PathBuf,
+        properties: &vk::PhysicalDeviceProperties,
+        device: Arc<RawDevice>,
+    ) -> VkResult<Self> {
+        let header = PipelineCacheHeader::new(properties);
+        let initial_data = fs::read(&path)
+            .ok()
+            .and_then(|bytes| PipelineCacheHeader::read(&bytes).map(|(h, rest)| (h, rest.to_vec())))
+            .filter(|(stored, _)| stored.matches(properties))
+            .map(|(_, data)| data)
+            .unwrap_or_default();
+
+        let create_info = vk::PipelineCacheCreateInfo::builder().initial_data(&initial_data);
+        let cache = unsafe { device.device.create_pipeline_cache(&create_info, None) }?;
+
+        Ok(Self {
+            cache,
+            path,
+            header,
+            device,
+        })
+    }
+
+    /// Default on-disk location, `<cache dir>/pilka/pipeline_cache.bin`.
+    pub fn default_path() -> PathBuf {
+        dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("pilka")
+            .join("pipeline_cache.bin")
+    }
+
+    /// Persist the current driver cache contents to disk, prefixed with the
+    /// header used to reject mismatched GPUs on the next load.
+    pub fn flush(&self) -> VkResult<()> {
+        let data = unsafe { self.device.device.get_pipeline_cache_data(self.cache) }?;
+        if let Some(parent) = self.path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Ok(mut file) = fs::File::create(&self.path) {
+            let _ = file.write_all(self.header.as_bytes());
+            let _ = file.write_all(&data);
+        }
+        Ok(())
+    }
+}
+
+impl std::ops::Deref for PipelineCache {
+    type Target = vk::PipelineCache;
+
+    fn deref(&self) -> &Self::Target {
+        &self.cache
+    }
+}
+
+impl Drop for PipelineCache {
+    fn drop(&mut self) {
+        let _ = self.flush();
+        unsafe { self.device.device.destroy_pipeline_cache(self.cache, None) };
+    }
+}
 
 pub struct VkRenderPass {
     pub render_pass: vk::RenderPass,
+    pub config: RenderPassConfig,
     pub device: Arc<RawDevice>,
 }
 
+/// How a [`VkRenderPass`] should be built: how many samples per pixel, and
+/// whether a depth attachment is present.
+#[derive(Clone, Copy)]
+pub struct RenderPassConfig {
+    pub samples: vk::SampleCountFlags,
+    pub depth_format: Option<vk::Format>,
+}
+
+impl Default for RenderPassConfig {
+    fn default() -> Self {
+        Self {
+            samples: vk::SampleCountFlags::TYPE_1,
+            depth_format: None,
+        }
+    }
+}
+
+impl RenderPassConfig {
+    /// Check `samples` against what the device supports for both color and
+    /// (when present) depth framebuffer attachments, falling back to a single
+    /// sample when the requested count is not supported.
+    pub fn validated(mut self, properties: &vk::PhysicalDeviceProperties) -> Self {
+        let mut limits = properties.limits.framebuffer_color_sample_counts;
+        if self.depth_format.is_some() {
+            limits &= properties.limits.framebuffer_depth_sample_counts;
+        }
+        if !limits.contains(self.samples) {
+            self.samples = vk::SampleCountFlags::TYPE_1;
+        }
+        self
+    }
+}
+
+impl VkRenderPass {
+    pub fn new(
+        color_format: vk::Format,
+        config: RenderPassConfig,
+        device: Arc<RawDevice>,
+    ) -> VkResult<Self> {
+        let multisampled = config.samples != vk::SampleCountFlags::TYPE_1;
+
+        let mut attachments = vec![vk::AttachmentDescription {
+            format: color_format,
+            samples: config.samples,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            // A multisampled color target is resolved into a single-sample
+            // image, so it is never presented directly.
+            final_layout: if multisampled {
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            } else {
+                vk::ImageLayout::PRESENT_SRC_KHR
+            },
+            ..Default::default()
+        }];
+        let color_reference = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        };
+
+        let depth_reference = config.depth_format.map(|format| {
+            attachments.push(vk::AttachmentDescription {
+                format,
+                samples: config.samples,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            });
+            vk::AttachmentReference {
+                attachment: (attachments.len() - 1) as u32,
+                layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            }
+        });
+
+        let resolve_reference = if multisampled {
+            attachments.push(vk::AttachmentDescription {
+                format: color_format,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::DONT_CARE,
+                store_op: vk::AttachmentStoreOp::STORE,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                ..Default::default()
+            });
+            Some([vk::AttachmentReference {
+                attachment: (attachments.len() - 1) as u32,
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            }])
+        } else {
+            None
+        };
+
+        let color_references = [color_reference];
+        let mut subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_references);
+        if let Some(reference) = &depth_reference {
+            subpass = subpass.depth_stencil_attachment(reference);
+        }
+        if let Some(references) = &resolve_reference {
+            subpass = subpass.resolve_attachments(references);
+        }
+        let subpasses = [subpass.build()];
+
+        let create_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses);
+
+        let render_pass = unsafe { device.device.create_render_pass(&create_info, None) }?;
+
+        Ok(Self {
+            render_pass,
+            config,
+            device,
+        })
+    }
+}
+
 impl std::ops::Deref for VkRenderPass {
     type Target = vk::RenderPass;
 
@@ -38,10 +304,47 @@ pub struct PipelineDescriptor {
     pub depth_stencil: vk::PipelineDepthStencilStateCreateInfo,
     pub color_blend: vk::PipelineColorBlendStateCreateInfo,
     pub dynamic_state_info: vk::PipelineDynamicStateCreateInfo,
+    pub patch_control_points: u32,
+}
+
+/// The blend presets a shader project can request without touching the
+/// individual `vk::BlendFactor` fields.
+#[derive(Clone, Copy, Debug)]
+pub enum BlendMode {
+    /// No blending; the fragment replaces the framebuffer.
+    Replace,
+    /// Standard `src_alpha`/`one_minus_src_alpha` alpha compositing.
+    Alpha,
+    /// Additive blending, useful for glow/particle effects.
+    Additive,
+}
+
+impl BlendMode {
+    fn attachment(self) -> vk::PipelineColorBlendAttachmentState {
+        let (blend_enable, src, dst) = match self {
+            BlendMode::Replace => (0, vk::BlendFactor::ONE, vk::BlendFactor::ZERO),
+            BlendMode::Alpha => (
+                1,
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendMode::Additive => (1, vk::BlendFactor::ONE, vk::BlendFactor::ONE),
+        };
+        vk::PipelineColorBlendAttachmentState {
+            blend_enable,
+            src_color_blend_factor: src,
+            dst_color_blend_factor: dst,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: src,
+            dst_alpha_blend_factor: dst,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::all(),
+        }
+    }
 }
 
 impl PipelineDescriptor {
-    fn new(shader_stages: Box<[vk::PipelineShaderStageCreateInfo]>) -> Self {
+    pub fn new(shader_stages: Box<[vk::PipelineShaderStageCreateInfo]>) -> Self {
         let vertex_input = vk::PipelineVertexInputStateCreateInfo {
             vertex_attribute_description_count: 0,
             vertex_binding_description_count: 0,
@@ -109,13 +412,348 @@ impl PipelineDescriptor {
             color_blend,
             dynamic_state,
             dynamic_state_info,
+            patch_control_points: 3,
+        }
+    }
+
+    /// Number of control points per patch for tessellation stages.
+    pub fn patch_control_points(mut self, points: u32) -> Self {
+        self.patch_control_points = points;
+        self
+    }
+
+    /// Select the primitive topology, e.g. `TRIANGLE_STRIP`, `LINE_LIST`, or
+    /// `POINT_LIST`.
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.input_assembly.topology = topology;
+        self
+    }
+
+    /// Set the face-culling mode; pass `vk::CullModeFlags::NONE` to draw both
+    /// sides.
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.rasterization.cull_mode = cull_mode;
+        self
+    }
+
+    /// Set the polygon fill mode, e.g. `LINE` for wireframe or `POINT`.
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.rasterization.polygon_mode = polygon_mode;
+        self
+    }
+
+    /// Set the winding order treated as front-facing.
+    pub fn front_face(mut self, front_face: vk::FrontFace) -> Self {
+        self.rasterization.front_face = front_face;
+        self
+    }
+
+    /// Set the multisample count. `VkPipeline::new` replaces it with the render
+    /// pass's sample count.
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.multisample.rasterization_samples = samples;
+        self
+    }
+
+    /// Enable depth testing and writing with the given compare op, matching a
+    /// render pass created with a depth attachment.
+    pub fn depth_test(mut self, compare_op: vk::CompareOp) -> Self {
+        self.depth_stencil.depth_test_enable = 1;
+        self.depth_stencil.depth_write_enable = 1;
+        self.depth_stencil.depth_compare_op = compare_op;
+        self
+    }
+
+    /// Apply a [`BlendMode`] preset to every color attachment.
+    pub fn blend(mut self, mode: BlendMode) -> Self {
+        let attachment = mode.attachment();
+        for state in self.color_blend_attachments.iter_mut() {
+            *state = attachment;
+        }
+        // The color-blend state holds a pointer into the attachments box, so
+        // rebuild it now that their contents have changed.
+        self.color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
+            .logic_op(vk::LogicOp::CLEAR)
+            .attachments(self.color_blend_attachments.as_ref())
+            .build();
+        self
+    }
+}
+
+/// Fold `field_hash` into `seed` with the classic boost `hash_combine`
+/// mixing step, so two runs that assemble the same state in the same order
+/// land on the same key.
+fn hash_combine(seed: u64, field_hash: u64) -> u64 {
+    seed ^ field_hash
+        .wrapping_add(0x9e37_79b9)
+        .wrapping_add(seed << 6)
+        .wrapping_add(seed >> 2)
+}
+
+/// Hash the raw bytes of any plain-old-data `vk` state struct.
+fn hash_bytes<T: Copy>(value: &T) -> u64 {
+    use std::hash::{Hash, Hasher};
+    // Safety: `T` is `Copy` POD coming straight out of the `vk` structs.
+    let bytes = unsafe {
+        std::slice::from_raw_parts((value as *const T) as *const u8, std::mem::size_of::<T>())
+    };
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The variable state that distinguishes one built pipeline from another. When
+/// the window resizes or a shader reloads we rebuild only if this fingerprint
+/// is new; an identical configuration reuses the cached `vk::Pipeline`.
+pub struct PipelineInfo {
+    pub vertex_shader_module: vk::ShaderModule,
+    pub fragment_shader_module: vk::ShaderModule,
+    pub extent: vk::Extent2D,
+    pub topology: vk::PrimitiveTopology,
+    pub cull_mode: vk::CullModeFlags,
+    pub rasterization_samples: vk::SampleCountFlags,
+    pub rasterization: vk::PipelineRasterizationStateCreateInfo,
+    pub depth_stencil: vk::PipelineDepthStencilStateCreateInfo,
+    pub color_blend_attachments: Box<[vk::PipelineColorBlendAttachmentState]>,
+    pub dynamic_states: Box<[vk::DynamicState]>,
+}
+
+impl PipelineInfo {
+    pub fn hash(&self) -> u64 {
+        let mut seed = hash_combine(0, hash_bytes(&self.vertex_shader_module));
+        seed = hash_combine(seed, hash_bytes(&self.fragment_shader_module));
+        seed = hash_combine(seed, hash_bytes(&self.extent));
+        seed = hash_combine(seed, hash_bytes(&self.topology));
+        let cull_mode_dynamic = self.dynamic_states.contains(&vk::DynamicState::CULL_MODE);
+        if !cull_mode_dynamic {
+            seed = hash_combine(seed, hash_bytes(&self.cull_mode));
+        }
+        // The sample count always changes the built pipeline (chunk0-4), so it
+        // must be part of the key regardless of the dynamic-state set.
+        seed = hash_combine(seed, hash_bytes(&self.rasterization_samples));
+        for attachment in self.color_blend_attachments.iter() {
+            seed = hash_combine(seed, hash_bytes(attachment));
+        }
+        // Fields that the dynamic-state set already makes mutable at draw time
+        // do not affect the built pipeline, so folding them in would only cost
+        // us cache hits. Only those fields are cleared; the rest of each state
+        // struct stays in the key.
+        let mut depth_stencil = self.depth_stencil;
+        if self
+            .dynamic_states
+            .contains(&vk::DynamicState::DEPTH_TEST_ENABLE)
+        {
+            depth_stencil.depth_test_enable = 0;
+        }
+        seed = hash_combine(seed, hash_bytes(&depth_stencil));
+        let mut rasterization = self.rasterization;
+        if cull_mode_dynamic {
+            rasterization.cull_mode = vk::CullModeFlags::NONE;
+        }
+        seed = hash_combine(seed, hash_bytes(&rasterization));
+        seed
+    }
+}
+
+/// A map of already-built pipelines, keyed by [`PipelineInfo::hash`], living
+/// alongside the device so repeated configurations are served from memory.
+#[derive(Default)]
+pub struct PipelineMap {
+    pipelines: std::collections::HashMap<u64, VkPipeline>,
+}
+
+impl PipelineMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the cached pipeline for `info`, building and inserting it via
+    /// `build` on a miss.
+    pub fn get_or_insert_with(
+        &mut self,
+        info: &PipelineInfo,
+        build: impl FnOnce() -> VkResult<VkPipeline>,
+    ) -> VkResult<&VkPipeline> {
+        use std::collections::hash_map::Entry;
+        match self.pipelines.entry(info.hash()) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => Ok(entry.insert(build()?)),
         }
     }
 }
 
+/// A single resource binding discovered by reflecting a shader stage. The host
+/// consults this table to know which set/binding to bind the standard uniform
+/// block (time, resolution, mouse, audio, ...) to each frame.
+#[derive(Clone, Copy, Debug)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    /// Number of array elements (`1` for a non-array binding).
+    pub count: u32,
+    pub stage_flags: vk::ShaderStageFlags,
+}
+
+/// Reflect a compiled SPIR-V module, returning its descriptor bindings and the
+/// combined push-constant range for `stage`.
+fn reflect_stage(
+    spirv: &[u32],
+    stage: vk::ShaderStageFlags,
+) -> VkResult<(Vec<ReflectedBinding>, Option<vk::PushConstantRange>)> {
+    let module = spirv_reflect::ShaderModule::load_u32_data(spirv)
+        .map_err(|_| vk::Result::ERROR_INITIALIZATION_FAILED)?;
+
+    let bindings = module
+        .enumerate_descriptor_bindings(None)
+        .map_err(|_| vk::Result::ERROR_INITIALIZATION_FAILED)?
+        .into_iter()
+        .map(|b| ReflectedBinding {
+            set: b.set,
+            binding: b.binding,
+            descriptor_type: vk::DescriptorType::from_raw(b.descriptor_type as i32),
+            // `count` is the product of the array dimensions, or 1 for a scalar
+            // binding; `max(1)` guards against a runtime-sized array reflecting
+            // as 0.
+            count: b.count.max(1),
+            stage_flags: stage,
+        })
+        .collect();
+
+    // Fold every push-constant block in this stage into one contiguous range;
+    // a stage can only ever have a single push-constant block in practice.
+    let push_constant = module
+        .enumerate_push_constant_blocks(None)
+        .map_err(|_| vk::Result::ERROR_INITIALIZATION_FAILED)?
+        .into_iter()
+        .fold(None, |acc: Option<(u32, u32)>, block| {
+            let lo = block.offset;
+            let hi = block.offset + block.size;
+            Some(match acc {
+                Some((a, b)) => (a.min(lo), b.max(hi)),
+                None => (lo, hi),
+            })
+        })
+        .map(|(offset, end)| vk::PushConstantRange {
+            stage_flags: stage,
+            offset,
+            size: end - offset,
+        });
+
+    Ok((bindings, push_constant))
+}
+
+/// One programmable stage feeding a pipeline: its module, which stage it is,
+/// the SPIR-V words kept around for reflection, and the entry point to invoke
+/// (so a single module exporting several entry points can be reused).
+pub struct ShaderStage<'a> {
+    pub module: vk::ShaderModule,
+    pub stage: vk::ShaderStageFlags,
+    pub spirv: &'a [u32],
+    pub entry_point: CString,
+}
+
+impl<'a> ShaderStage<'a> {
+    /// Stage with the conventional `main` entry point.
+    pub fn new(module: vk::ShaderModule, stage: vk::ShaderStageFlags, spirv: &'a [u32]) -> Self {
+        Self {
+            module,
+            stage,
+            spirv,
+            entry_point: CString::new("main").unwrap(),
+        }
+    }
+
+    /// Select a named entry point other than `main`.
+    pub fn entry_point(mut self, name: &str) -> Self {
+        self.entry_point = CString::new(name).unwrap();
+        self
+    }
+}
+
+/// Build the descriptor set layouts and push-constant ranges for a set of
+/// stages by reflecting each one, grouping bindings by set.
+fn build_layout_resources(
+    stages: &[(vk::ShaderStageFlags, &[u32])],
+    device: &RawDevice,
+) -> VkResult<(
+    Vec<vk::DescriptorSetLayout>,
+    Vec<vk::PushConstantRange>,
+    Vec<ReflectedBinding>,
+)> {
+    // Collect every stage's bindings, merging duplicates: a uniform/storage
+    // block declared in more than one stage (e.g. a shared time/resolution
+    // block in both vertex and fragment) must appear once per `(set, binding)`
+    // with the stage flags OR-ed together, or `create_descriptor_set_layout`
+    // rejects the duplicate binding number.
+    let mut bindings: Vec<ReflectedBinding> = Vec::new();
+    let mut push_range: Option<vk::PushConstantRange> = None;
+    for (stage, spirv) in stages {
+        let (stage_bindings, push_constant) = reflect_stage(spirv, *stage)?;
+        for binding in stage_bindings {
+            match bindings
+                .iter_mut()
+                .find(|b| b.set == binding.set && b.binding == binding.binding)
+            {
+                Some(existing) => {
+                    existing.stage_flags |= binding.stage_flags;
+                    existing.count = existing.count.max(binding.count);
+                }
+                None => bindings.push(binding),
+            }
+        }
+        // Fold each stage's push-constant range into a single combined range,
+        // OR-ing the stage flags rather than emitting one range per stage.
+        if let Some(range) = push_constant {
+            push_range = Some(match push_range {
+                Some(acc) => {
+                    let offset = acc.offset.min(range.offset);
+                    let end = (acc.offset + acc.size).max(range.offset + range.size);
+                    vk::PushConstantRange {
+                        stage_flags: acc.stage_flags | range.stage_flags,
+                        offset,
+                        size: end - offset,
+                    }
+                }
+                None => range,
+            });
+        }
+    }
+    let push_constant_ranges: Vec<_> = push_range.into_iter().collect();
+
+    let max_set = bindings.iter().map(|b| b.set).max();
+    let mut descriptor_set_layouts = Vec::new();
+    if let Some(max_set) = max_set {
+        for set in 0..=max_set {
+            let set_bindings: Vec<_> = bindings
+                .iter()
+                .filter(|b| b.set == set)
+                .map(|b| vk::DescriptorSetLayoutBinding {
+                    binding: b.binding,
+                    descriptor_type: b.descriptor_type,
+                    descriptor_count: b.count,
+                    stage_flags: b.stage_flags,
+                    ..Default::default()
+                })
+                .collect();
+            let create_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&set_bindings);
+            let layout = unsafe {
+                device
+                    .device
+                    .create_descriptor_set_layout(&create_info, None)
+            }?;
+            descriptor_set_layouts.push(layout);
+        }
+    }
+
+    Ok((descriptor_set_layouts, push_constant_ranges, bindings))
+}
+
 pub struct VkPipeline {
     pub pipelines: Vec<vk::Pipeline>,
     pub pipeline_layout: vk::PipelineLayout,
+    pub descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    pub bindings: Vec<ReflectedBinding>,
     device: Arc<RawDevice>,
     pub viewports: [vk::Viewport; 1],
     pub scissors: [vk::Rect2D; 1],
@@ -123,13 +761,23 @@ pub struct VkPipeline {
 
 impl VkPipeline {
     pub fn new(
-        vertex_shader_module: vk::ShaderModule,
-        fragment_shader_module: vk::ShaderModule,
+        stages: &[ShaderStage],
+        descriptor: &PipelineDescriptor,
         extent: vk::Extent2D,
         render_pass: &VkRenderPass,
+        pipeline_cache: &PipelineCache,
         device: Arc<RawDevice>,
     ) -> VkResult<Self> {
-        let layout_create_info = vk::PipelineLayoutCreateInfo::default();
+        // Reflect every stage to discover the descriptor bindings and
+        // push-constant ranges the shaders actually declare, so a plain
+        // `uniform` block in GLSL is wired up without any host-side convention.
+        let reflect_stages: Vec<_> = stages.iter().map(|s| (s.stage, s.spirv)).collect();
+        let (descriptor_set_layouts, push_constant_ranges, bindings) =
+            build_layout_resources(&reflect_stages, &device)?;
+
+        let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&descriptor_set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
 
         let pipeline_layout = unsafe {
             device
@@ -137,36 +785,28 @@ impl VkPipeline {
                 .create_pipeline_layout(&layout_create_info, None)
         }?;
 
-        let shader_entry_name = CString::new("main").unwrap();
-        let shader_stage_create_infos = [
-            vk::PipelineShaderStageCreateInfo {
-                module: vertex_shader_module,
-                p_name: shader_entry_name.as_ptr(),
-                stage: vk::ShaderStageFlags::VERTEX,
-                ..Default::default()
-            },
-            vk::PipelineShaderStageCreateInfo {
-                s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
-                module: fragment_shader_module,
-                p_name: shader_entry_name.as_ptr(),
-                stage: vk::ShaderStageFlags::FRAGMENT,
+        // Each stage owns its entry-point `CString`, so the pointers we hand to
+        // Vulkan stay valid for the whole create call.
+        let shader_stage_create_infos: Vec<_> = stages
+            .iter()
+            .map(|s| vk::PipelineShaderStageCreateInfo {
+                module: s.module,
+                p_name: s.entry_point.as_ptr(),
+                stage: s.stage,
                 ..Default::default()
-            },
-        ];
-
-        let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo {
-            // vertex_attribute_description_count: vertex_input_attribute_descriptions.len()
-            //     as u32,
-            // p_vertex_attribute_descriptions: vertex_input_attribute_descriptions.as_ptr(),
-            // vertex_binding_description_count: vertex_input_binding_descriptions.len() as u32,
-            // p_vertex_binding_descriptions: vertex_input_binding_descriptions.as_ptr(),
-            ..Default::default()
-        };
+            })
+            .collect();
+
+        // Tessellation needs a patch-control-point count; only attach the
+        // state when a tessellation stage is actually present.
+        let has_tessellation = stages.iter().any(|s| {
+            s.stage == vk::ShaderStageFlags::TESSELLATION_CONTROL
+                || s.stage == vk::ShaderStageFlags::TESSELLATION_EVALUATION
+        });
+        let tessellation_state = vk::PipelineTessellationStateCreateInfo::builder()
+            .patch_control_points(descriptor.patch_control_points)
+            .build();
 
-        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
-            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-            ..Default::default()
-        };
         let viewports = [vk::Viewport {
             x: 0.0,
             y: extent.height as f32,
@@ -183,51 +823,41 @@ impl VkPipeline {
             .scissors(&scissors)
             .viewports(&viewports);
 
-        let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
-            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
-            line_width: 1.0,
-            polygon_mode: vk::PolygonMode::FILL,
-            cull_mode: vk::CullModeFlags::BACK,
-            ..Default::default()
-        };
-        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
-            rasterization_samples: vk::SampleCountFlags::TYPE_1,
-            ..Default::default()
-        };
-
-        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-            blend_enable: 0,
-            src_color_blend_factor: vk::BlendFactor::SRC_COLOR,
-            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_DST_COLOR,
-            color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::ZERO,
-            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            alpha_blend_op: vk::BlendOp::ADD,
-            color_write_mask: vk::ColorComponentFlags::all(),
-        }];
-        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
-            .logic_op(vk::LogicOp::CLEAR)
-            .attachments(&color_blend_attachment_states);
-
-        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-        let dynamic_state_info =
-            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_state);
+        // The multisample and depth-stencil state must agree with the render
+        // pass, so take the sample count and depth presence straight from it
+        // rather than relying on the caller to keep the descriptor in sync.
+        let mut multisample = descriptor.multisample;
+        multisample.rasterization_samples = render_pass.config.samples;
+        let mut depth_stencil = descriptor.depth_stencil;
+        if render_pass.config.depth_format.is_some() {
+            depth_stencil.depth_test_enable = 1;
+            depth_stencil.depth_write_enable = 1;
+            // Keep an explicit compare op, but replace the `ALWAYS` default
+            // (which disables useful depth testing) with the common choice.
+            if depth_stencil.depth_compare_op == vk::CompareOp::ALWAYS {
+                depth_stencil.depth_compare_op = vk::CompareOp::LESS_OR_EQUAL;
+            }
+        }
 
-        let graphic_pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+        let mut graphic_pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(&shader_stage_create_infos)
-            .vertex_input_state(&vertex_input_state_info)
-            .input_assembly_state(&vertex_input_assembly_state_info)
+            .vertex_input_state(&descriptor.vertex_input)
+            .input_assembly_state(&descriptor.input_assembly)
             .viewport_state(&viewport_state_info)
-            .rasterization_state(&rasterization_info)
-            .multisample_state(&multisample_state_info)
-            .color_blend_state(&color_blend_state)
-            .dynamic_state(&dynamic_state_info)
+            .rasterization_state(&descriptor.rasterization)
+            .multisample_state(&multisample)
+            .depth_stencil_state(&depth_stencil)
+            .color_blend_state(&descriptor.color_blend)
+            .dynamic_state(&descriptor.dynamic_state_info)
             .layout(pipeline_layout)
             .render_pass(render_pass.render_pass);
+        if has_tessellation {
+            graphic_pipeline_info = graphic_pipeline_info.tessellation_state(&tessellation_state);
+        }
 
         let graphics_pipelines = unsafe {
             device.device.create_graphics_pipelines(
-                vk::PipelineCache::null(),
+                pipeline_cache.cache,
                 &[graphic_pipeline_info.build()],
                 None,
             )
@@ -237,6 +867,8 @@ impl VkPipeline {
         Ok(VkPipeline {
             pipelines: graphics_pipelines,
             pipeline_layout,
+            descriptor_set_layouts,
+            bindings,
             device,
             viewports,
             scissors,
@@ -258,6 +890,94 @@ impl Drop for VkPipeline {
             self.device
                 .device
                 .destroy_pipeline_layout(self.pipeline_layout, None);
+
+            for layout in &self.descriptor_set_layouts {
+                self.device
+                    .device
+                    .destroy_descriptor_set_layout(*layout, None);
+            }
+        }
+    }
+}
+
+/// A compute pipeline for GPGPU/feedback sketches that write to a storage
+/// image rather than rasterizing. Its layout is reflected from the compute
+/// shader the same way [`VkPipeline`]'s is.
+pub struct VkComputePipeline {
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    pub bindings: Vec<ReflectedBinding>,
+    device: Arc<RawDevice>,
+}
+
+impl VkComputePipeline {
+    pub fn new(
+        stage: &ShaderStage,
+        pipeline_cache: &PipelineCache,
+        device: Arc<RawDevice>,
+    ) -> VkResult<Self> {
+        let (descriptor_set_layouts, push_constant_ranges, bindings) = build_layout_resources(
+            &[(vk::ShaderStageFlags::COMPUTE, stage.spirv)],
+            &device,
+        )?;
+
+        let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&descriptor_set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+
+        let pipeline_layout = unsafe {
+            device
+                .device
+                .create_pipeline_layout(&layout_create_info, None)
+        }?;
+
+        let stage_create_info = vk::PipelineShaderStageCreateInfo {
+            module: stage.module,
+            p_name: stage.entry_point.as_ptr(),
+            stage: vk::ShaderStageFlags::COMPUTE,
+            ..Default::default()
+        };
+
+        let compute_pipeline_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage_create_info)
+            .layout(pipeline_layout);
+
+        let pipelines = unsafe {
+            device.device.create_compute_pipelines(
+                pipeline_cache.cache,
+                &[compute_pipeline_info.build()],
+                None,
+            )
+        }
+        .expect("Unable to create compute pipeline");
+
+        Ok(Self {
+            pipeline: pipelines[0],
+            pipeline_layout,
+            descriptor_set_layouts,
+            bindings,
+            device,
+        })
+    }
+
+    pub fn get(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+}
+
+impl Drop for VkComputePipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device.destroy_pipeline(self.pipeline, None);
+            self.device
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            for layout in &self.descriptor_set_layouts {
+                self.device
+                    .device
+                    .destroy_descriptor_set_layout(*layout, None);
+            }
         }
     }
 }